@@ -1,11 +1,13 @@
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use directories::ProjectDirs;
+use futures::StreamExt;
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use tokio::io::{AsyncBufReadExt, BufReader};
 use serde::{Deserialize, Serialize};
 use serde_json::from_str;
 use std::collections::HashSet;
 use std::error::Error;
-use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::{fmt, fs, io, str};
 use url::Url;
@@ -13,8 +15,8 @@ use url::Url;
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 struct Cli {
-    /// URL to play from
-    url: String,
+    /// URL to play from (defaults to the last played playlist)
+    url: Option<String>,
 
     /// Use verbose output
     #[arg(short, long)]
@@ -35,6 +37,73 @@ struct Cli {
     /// Custom mpv arguments
     #[arg(long)]
     mpv_arguments: Option<String>,
+
+    /// Number of songs to download in parallel
+    #[arg(long)]
+    parallel: Option<usize>,
+
+    /// Play straight from the cache without any network access
+    #[arg(long)]
+    offline: bool,
+
+    /// List cached playlists with song counts and sizes, then exit
+    #[arg(long)]
+    list_cached: bool,
+
+    /// Audio format to extract to
+    #[arg(long, value_enum)]
+    format: Option<Format>,
+
+    /// Embed metadata and thumbnail into downloaded files
+    #[arg(long)]
+    embed_metadata: bool,
+}
+
+/// Audio format passed through to yt-dlp's post-processing.
+#[derive(ValueEnum, Clone, Copy, Debug, Default)]
+#[value(rename_all = "lower")]
+enum Format {
+    Opus,
+    Mp3,
+    M4a,
+    /// Keep yt-dlp's best available audio without re-encoding.
+    #[default]
+    Best,
+}
+
+impl Format {
+    /// The file extension downloads of this format are expected to carry, or
+    /// `None` when any extension is acceptable.
+    fn extension(self) -> Option<&'static str> {
+        match self {
+            Format::Opus => Some("opus"),
+            Format::Mp3 => Some("mp3"),
+            Format::M4a => Some("m4a"),
+            Format::Best => None,
+        }
+    }
+
+    /// The yt-dlp arguments that select and re-encode to this format.
+    fn yt_dlp_args(self) -> Vec<&'static str> {
+        match self.extension() {
+            Some(ext) => vec!["--audio-format", ext, "--audio-quality", "0"],
+            None => Vec::new(),
+        }
+    }
+}
+
+/// Persistent defaults loaded from `config_dir()/config.toml`.
+///
+/// Every field is optional so that a partial config only overrides the
+/// built-in defaults for the keys it sets; CLI flags in turn override these.
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+struct Config {
+    yt_dlp_arguments: Option<String>,
+    mpv_arguments: Option<String>,
+    shuffle: bool,
+    format: Option<String>,
+    parallel: Option<usize>,
 }
 
 #[derive(Debug)]
@@ -48,32 +117,133 @@ impl fmt::Display for PlaylistError {
 
 impl Error for PlaylistError {}
 
-fn extract_id(url: &str) -> Result<String, Box<dyn Error>> {
+/// The kind of thing a user-supplied URL points at, once resolved.
+///
+/// A single video is cached as a one-song "pseudo-playlist" under its video
+/// id, a channel is turned into that channel's uploads playlist, and a plain
+/// `list=` URL keeps the existing playlist behaviour.
+enum ResolvedTarget {
+    Playlist(String),
+    Video(String),
+    Channel(String),
+}
+
+fn extract_id(url: &str) -> Result<ResolvedTarget, Box<dyn Error>> {
     let parsed_url = Url::parse(url).map_err(|e| format!("Invalid URL format: {e}"))?;
 
-    let mut queries = parsed_url.query_pairs();
+    let host = parsed_url.host_str().unwrap_or_default();
+    let path = parsed_url.path();
 
-    if let Some((_, id)) = queries.find(|(parameter, _)| parameter == "list") {
-        Ok(id.to_string())
-    } else {
-        Err(Box::new(PlaylistError(
-            "Could not find a 'list' parameter in the URL".to_string(),
-        )))
+    // A `list` parameter always denotes a playlist, even on a youtu.be short
+    // link or a watch URL (e.g. `youtu.be/<id>?list=...&index=N`, which is
+    // exactly what YouTube produces when sharing a video from inside a
+    // playlist).
+    if let Some((_, id)) = parsed_url.query_pairs().find(|(parameter, _)| parameter == "list") {
+        return Ok(ResolvedTarget::Playlist(id.to_string()));
+    }
+
+    // youtu.be short links carry the video id directly in the path.
+    if host.ends_with("youtu.be") {
+        let id = path.trim_start_matches('/');
+        if id.is_empty() {
+            return Err(Box::new(PlaylistError(
+                "Could not find a video id in the short URL".to_string(),
+            )));
+        }
+        return Ok(ResolvedTarget::Video(id.to_string()));
+    }
+
+    let is_youtube_host = host == "youtube.com" || host.ends_with(".youtube.com");
+
+    // A bare watch URL resolves to the single video it names.
+    if is_youtube_host {
+        if let Some((_, id)) = parsed_url.query_pairs().find(|(parameter, _)| parameter == "v") {
+            return Ok(ResolvedTarget::Video(id.to_string()));
+        }
+    }
+
+    // Channel, handle, and user URLs resolve to the channel's uploads playlist.
+    if is_youtube_host
+        && (path.starts_with("/channel/") || path.starts_with("/@") || path.starts_with("/user/"))
+    {
+        return Ok(ResolvedTarget::Channel(url.to_string()));
     }
+
+    Err(Box::new(PlaylistError(
+        "Could not resolve a playlist, video, or channel from the URL".to_string(),
+    )))
+}
+
+/// Resolve a channel/handle/user URL to its uploads playlist id via yt-dlp.
+///
+/// A flattened channel page's top-level `id` is the *channel* id
+/// (`UC...`), not a playlist id, so it can't be passed straight to
+/// `playlist?list=`. YouTube mirrors every channel's uploads as a playlist
+/// with the same id but a `UU` prefix, so that's derived here instead of
+/// trusting the flat-playlist JSON to already name a playlist.
+fn resolve_channel_uploads(channel_url: &str) -> Result<String, Box<dyn Error>> {
+    let mut yt_dlp = Command::new("yt-dlp");
+    yt_dlp.args(["--flat-playlist", "--playlist-items", "0", "-J", channel_url]);
+    let output = yt_dlp.output()?;
+    let stdout = str::from_utf8(&output.stdout)?;
+    let playlist_json: Playlist = from_str(stdout)?;
+
+    let stderr = str::from_utf8(&output.stderr)?;
+    eprintln!("{stderr}");
+
+    let channel_id = playlist_json.id;
+    Ok(match channel_id.strip_prefix("UC") {
+        Some(rest) => format!("UU{rest}"),
+        None => channel_id,
+    })
+}
+
+fn project_dirs() -> Result<ProjectDirs, Box<dyn Error>> {
+    ProjectDirs::from("dev", "hawksley", "yt-play").ok_or_else(|| {
+        Box::new(io::Error::new(
+            io::ErrorKind::NotFound,
+            "Home directory could not be found",
+        )) as Box<dyn Error>
+    })
 }
 
 fn get_playlist_directory(playlist_id: &str) -> Result<PathBuf, Box<dyn Error>> {
-    let proj_dirs = ProjectDirs::from("dev", "hawksley", "yt-play").ok_or_else(|| {
-        io::Error::new(io::ErrorKind::NotFound, "Home directory could not be found")
-    })?;
+    Ok(project_dirs()?.cache_dir().join(playlist_id))
+}
+
+fn load_config() -> Result<Config, Box<dyn Error>> {
+    let config_path = project_dirs()?.config_dir().join("config.toml");
+    match fs::read_to_string(&config_path) {
+        Ok(contents) => Ok(toml::from_str(&contents)?),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+        Err(e) => Err(Box::new(e)),
+    }
+}
+
+fn last_url_path() -> Result<PathBuf, Box<dyn Error>> {
+    Ok(project_dirs()?.config_dir().join("last_url"))
+}
 
-    let playlist_directory = proj_dirs.cache_dir().join(playlist_id);
+fn read_last_url() -> Result<Option<String>, Box<dyn Error>> {
+    match fs::read_to_string(last_url_path()?) {
+        Ok(contents) => Ok(Some(contents.trim().to_string())),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(Box::new(e)),
+    }
+}
 
-    Ok(playlist_directory)
+fn write_last_url(url: &str) -> Result<(), Box<dyn Error>> {
+    let path = last_url_path()?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, url)?;
+    Ok(())
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 struct Playlist {
+    id: String,
     title: String,
     entries: Vec<Song>,
 }
@@ -82,6 +252,57 @@ struct Playlist {
 struct Song {
     id: String,
     title: String,
+    #[serde(default)]
+    duration: Option<f64>,
+    #[serde(default)]
+    uploader: Option<String>,
+    #[serde(default)]
+    channel: Option<String>,
+    #[serde(default)]
+    availability: Option<String>,
+    #[serde(default)]
+    live_status: Option<String>,
+}
+
+impl Song {
+    /// Whether the entry can actually be downloaded and played.
+    ///
+    /// Private, deleted, and currently-live (or upcoming) entries are skipped
+    /// so they don't masquerade as failed downloads.
+    fn is_playable(&self) -> bool {
+        let unavailable = matches!(
+            self.availability.as_deref(),
+            Some("private") | Some("needs_auth") | Some("premium_only") | Some("subscriber_only")
+        );
+        let deleted = matches!(
+            self.title.as_str(),
+            "[Private video]" | "[Deleted video]" | "[Unavailable video]"
+        );
+        let live = matches!(
+            self.live_status.as_deref(),
+            Some("is_live") | Some("is_upcoming")
+        );
+
+        !(unavailable || deleted || live)
+    }
+
+    /// A one-line human-readable summary for verbose output.
+    fn describe(&self) -> String {
+        let mut parts = vec![self.title.clone()];
+        if let Some(uploader) = self.uploader.as_ref().or(self.channel.as_ref()) {
+            parts.push(format!("by {uploader}"));
+        }
+        if let Some(duration) = self.duration {
+            parts.push(format!("[{}]", format_duration(duration)));
+        }
+        parts.join(" ")
+    }
+}
+
+/// Format a duration in seconds as `m:ss`.
+fn format_duration(seconds: f64) -> String {
+    let total = seconds as u64;
+    format!("{}:{:02}", total / 60, total % 60)
 }
 
 fn fetch_playlist_data(playlist_id: &str) -> Result<Playlist, Box<dyn Error>> {
@@ -90,7 +311,10 @@ fn fetch_playlist_data(playlist_id: &str) -> Result<Playlist, Box<dyn Error>> {
     yt_dlp.args(["--flat-playlist", "-J", &playlist_url]);
     let output = yt_dlp.output()?;
     let stdout = str::from_utf8(&output.stdout)?;
-    let playlist_json: Playlist = from_str(stdout)?;
+    let mut playlist_json: Playlist = from_str(stdout)?;
+
+    // Drop entries that can't be played so they don't become failed downloads.
+    playlist_json.entries.retain(Song::is_playable);
 
     let stderr = str::from_utf8(&output.stderr)?;
     eprintln!("{stderr}");
@@ -98,6 +322,26 @@ fn fetch_playlist_data(playlist_id: &str) -> Result<Playlist, Box<dyn Error>> {
     Ok(playlist_json)
 }
 
+fn fetch_video_data(video_id: &str) -> Result<Playlist, Box<dyn Error>> {
+    let video_url = format!("https://www.youtube.com/watch?v={video_id}");
+    let mut yt_dlp = Command::new("yt-dlp");
+    yt_dlp.args(["-J", &video_url]);
+    let output = yt_dlp.output()?;
+    let stdout = str::from_utf8(&output.stdout)?;
+    let song: Song = from_str(stdout)?;
+
+    let stderr = str::from_utf8(&output.stderr)?;
+    eprintln!("{stderr}");
+
+    // Wrap the single video in a one-song pseudo-playlist so the rest of the
+    // pipeline can treat every target uniformly.
+    Ok(Playlist {
+        id: song.id.clone(),
+        title: song.title.clone(),
+        entries: vec![song],
+    })
+}
+
 fn list_files_in_directory(directory: &PathBuf) -> Result<Vec<PathBuf>, Box<dyn Error>> {
     let entries = fs::read_dir(directory)?;
     let mut files = Vec::new();
@@ -114,34 +358,252 @@ fn list_files_in_directory(directory: &PathBuf) -> Result<Vec<PathBuf>, Box<dyn
     Ok(files)
 }
 
-fn download_songs(
+/// Read a cached playlist's stored title, if `playlist.json` is present.
+fn read_cached_title(playlist_directory: &Path) -> Option<String> {
+    load_cached_playlist(playlist_directory).map(|playlist| playlist.title)
+}
+
+/// Format a byte count as a human-readable size.
+fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{size:.1} {}", UNITS[unit])
+}
+
+/// Print every cached playlist with its title, song count, and total size.
+fn list_cached() -> Result<(), Box<dyn Error>> {
+    let cache_dir = project_dirs()?.cache_dir().to_path_buf();
+    if !cache_dir.exists() {
+        println!("No cached playlists.");
+        return Ok(());
+    }
+
+    for entry in fs::read_dir(&cache_dir)? {
+        let entry = entry?;
+        if !entry.metadata()?.is_dir() {
+            continue;
+        }
+
+        let directory = entry.path();
+        let title = read_cached_title(&directory)
+            .unwrap_or_else(|| entry.file_name().to_string_lossy().into_owned());
+
+        let files = list_files_in_directory(&directory)?;
+        let count = files.iter().filter(|f| !is_metadata_file(f)).count();
+        let size: u64 = files
+            .iter()
+            .filter_map(|f| fs::metadata(f).ok())
+            .map(|m| m.len())
+            .sum();
+
+        println!("{title} — {count} songs, {}", human_size(size));
+    }
+
+    Ok(())
+}
+
+/// Files the cache stores for bookkeeping rather than playback.
+fn is_metadata_file(path: &Path) -> bool {
+    matches!(
+        path.file_name().and_then(|name| name.to_str()),
+        Some("playlist.json") | Some("playlist.m3u8")
+    )
+}
+
+/// Load a cached playlist from its persisted `playlist.json`.
+fn load_cached_playlist(playlist_directory: &Path) -> Option<Playlist> {
+    let contents = fs::read_to_string(playlist_directory.join("playlist.json")).ok()?;
+    from_str(&contents).ok()
+}
+
+/// Build a `.m3u8` playlist listing the downloaded files in playlist order.
+///
+/// Each `Song.id` is matched to its file with the same `filename.contains(id)`
+/// rule used when deciding which songs still need downloading.
+fn build_m3u(
+    playlist: &Playlist,
+    playlist_directory: &PathBuf,
+) -> Result<PathBuf, Box<dyn Error>> {
+    let files = list_files_in_directory(playlist_directory)?;
+
+    let mut m3u = String::from("#EXTM3U\n");
+    for song in &playlist.entries {
+        let matched = files.iter().find(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.contains(&song.id))
+        });
+
+        if let Some(name) = matched.and_then(|p| p.file_name()).and_then(|n| n.to_str()) {
+            m3u.push_str(name);
+            m3u.push('\n');
+        }
+    }
+
+    let m3u_path = playlist_directory.join("playlist.m3u8");
+    fs::write(&m3u_path, m3u)?;
+    Ok(m3u_path)
+}
+
+/// Parse a yt-dlp `--newline --progress` line into a download percentage.
+fn parse_progress(line: &str) -> Option<f64> {
+    if !line.contains("[download]") {
+        return None;
+    }
+    line.split_whitespace()
+        .find_map(|token| token.strip_suffix('%').and_then(|p| p.parse::<f64>().ok()))
+}
+
+/// The download-tuning knobs threaded from the CLI/config down to yt-dlp.
+///
+/// Bundled into one struct rather than passed positionally so that adding a
+/// new knob doesn't grow every function in the download pipeline by another
+/// parameter.
+struct DownloadOptions<'a> {
+    yt_dlp_arguments: &'a str,
+    parallel: usize,
+    format: Format,
+    embed_metadata: bool,
+}
+
+/// Download a single song, rendering its progress on a dedicated bar.
+async fn download_one(
+    id: &str,
+    playlist_directory: &PathBuf,
+    options: &DownloadOptions<'_>,
+    multi: &MultiProgress,
+) -> Result<(), String> {
+    let bar = multi.add(ProgressBar::new(100));
+    bar.set_style(
+        ProgressStyle::with_template("{msg:>12} [{bar:40}] {pos:>3}%")
+            .unwrap()
+            .progress_chars("=> "),
+    );
+    bar.set_message(id.to_string());
+
+    let mut yt_dlp = tokio::process::Command::new("yt-dlp");
+    yt_dlp
+        .current_dir(playlist_directory)
+        .arg("-o")
+        .arg("%(title)s [%(id)s].%(ext)s")
+        .arg("-x")
+        .args(options.format.yt_dlp_args())
+        .arg("--newline")
+        .arg("--progress")
+        .arg(format!("https://www.youtube.com/watch?v={id}"))
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped());
+
+    if options.embed_metadata {
+        yt_dlp.arg("--embed-metadata").arg("--embed-thumbnail");
+    }
+
+    if !options.yt_dlp_arguments.is_empty() {
+        yt_dlp.args(options.yt_dlp_arguments.split_whitespace());
+    }
+
+    let mut child = yt_dlp.spawn().map_err(|e| format!("{id}: {e}"))?;
+
+    let stdout = child.stdout.take();
+    let stderr = child.stderr.take();
+
+    // Drain stdout (for progress) and stderr (for diagnostics) concurrently so
+    // neither pipe's buffer fills up and stalls yt-dlp.
+    let progress = async {
+        if let Some(stdout) = stdout {
+            let mut lines = BufReader::new(stdout).lines();
+            while let Some(line) = lines.next_line().await.map_err(|e| format!("{id}: {e}"))? {
+                if let Some(percent) = parse_progress(&line) {
+                    bar.set_position(percent as u64);
+                }
+            }
+        }
+        Ok::<_, String>(())
+    };
+
+    // Keep only the last couple of lines so a failure message stays short
+    // while still carrying the actual reason (age-restricted, unavailable,
+    // network error, etc.).
+    let diagnostics = async {
+        let mut last_lines = Vec::new();
+        if let Some(stderr) = stderr {
+            let mut lines = BufReader::new(stderr).lines();
+            while let Some(line) = lines.next_line().await.map_err(|e| format!("{id}: {e}"))? {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                last_lines.push(line);
+                if last_lines.len() > 2 {
+                    last_lines.remove(0);
+                }
+            }
+        }
+        Ok::<_, String>(last_lines)
+    };
+
+    let (progress_result, diagnostics_result) = tokio::join!(progress, diagnostics);
+    progress_result?;
+    let last_lines = diagnostics_result?;
+
+    let status = child.wait().await.map_err(|e| format!("{id}: {e}"))?;
+    bar.finish_and_clear();
+
+    if status.success() {
+        Ok(())
+    } else if last_lines.is_empty() {
+        Err(format!("{id}: yt-dlp exited with {status}"))
+    } else {
+        Err(format!("{id}: yt-dlp exited with {status}: {}", last_lines.join(" | ")))
+    }
+}
+
+async fn download_songs(
     songs: &[Song],
     playlist_directory: &PathBuf,
-    yt_dlp_arguments: &str,
+    options: &DownloadOptions<'_>,
 ) -> Result<(), Box<dyn Error>> {
     let valid_ids: HashSet<&String> = songs.iter().map(|s| &s.id).collect();
     let mut found_ids: HashSet<String> = HashSet::new();
 
     let files = list_files_in_directory(playlist_directory)?;
 
+    let wanted_extension = options.format.extension();
+
     for path in files {
+        if is_metadata_file(&path) {
+            continue;
+        }
+
         let Some(filename) = path.file_name().and_then(|name| name.to_str()) else {
             continue;
         };
 
-        let mut matches_playlist = false;
-
-        for id in &valid_ids {
-            if filename.contains(*id) {
-                matches_playlist = true;
-                found_ids.insert((*id).clone());
-                break;
+        let matched_id = valid_ids.iter().find(|id| filename.contains(**id));
+
+        match matched_id {
+            Some(id) => {
+                // A file in a different format is stale: drop it so the song is
+                // re-downloaded in the requested format.
+                let extension_matches = wanted_extension.is_none_or(|ext| {
+                    path.extension().and_then(|e| e.to_str()) == Some(ext)
+                });
+
+                if extension_matches {
+                    found_ids.insert((*id).clone());
+                } else {
+                    println!("Deleting stale-format file: {}", path.display());
+                    fs::remove_file(path)?;
+                }
+            }
+            None => {
+                println!("Deleting erroneous file: {}", path.display());
+                fs::remove_file(path)?;
             }
-        }
-
-        if !matches_playlist {
-            println!("Deleting erroneous file: {}", path.display());
-            fs::remove_file(path)?;
         }
     }
 
@@ -157,58 +619,79 @@ fn download_songs(
 
     println!("Downloading {} missing songs...", missing_ids.len());
 
-    let mut yt_dlp = Command::new("yt-dlp");
-
-    yt_dlp
-        .current_dir(playlist_directory)
-        .arg("--batch-file")
-        .arg("-")
-        .arg("-o")
-        .arg("%(title)s [%(id)s].%(ext)s")
-        .arg("-x")
-        .stdin(std::process::Stdio::piped());
-
-    if !yt_dlp_arguments.is_empty() {
-        yt_dlp.args(yt_dlp_arguments.split_whitespace());
-    }
-
-    let mut child = yt_dlp.spawn()?;
-
-    if let Some(mut stdin) = child.stdin.take() {
-        for id in missing_ids {
-            writeln!(stdin, "https://www.youtube.com/watch?v={id}")?;
+    let multi = MultiProgress::new();
+    let overall = multi.add(ProgressBar::new(missing_ids.len() as u64));
+    overall.set_style(
+        ProgressStyle::with_template("{msg:>12} [{bar:40}] {pos}/{len} songs")
+            .unwrap()
+            .progress_chars("=> "),
+    );
+    overall.set_message("overall");
+
+    // Run up to `parallel` downloads concurrently, keeping a dedicated bar per
+    // active job, and collect per-id failures instead of aborting the batch.
+    let failures: Vec<String> = futures::stream::iter(missing_ids)
+        .map(|id| {
+            let multi = &multi;
+            let overall = &overall;
+            async move {
+                let result = download_one(id, playlist_directory, options, multi).await;
+                overall.inc(1);
+                result.err()
+            }
+        })
+        .buffer_unordered(options.parallel.max(1))
+        .filter_map(|err| async move { err })
+        .collect()
+        .await;
+
+    overall.finish_and_clear();
+
+    if !failures.is_empty() {
+        eprintln!("Failed to download {} song(s):", failures.len());
+        for failure in &failures {
+            eprintln!("  {failure}");
         }
     }
 
-    let status = child.wait()?;
-
-    if !status.success() {
-        return Err(Box::new(PlaylistError(
-            "yt-dlp failed to download some files".into(),
-        )));
-    }
-
     Ok(())
 }
 
-fn update_playlist(
+async fn update_playlist(
+    target: &ResolvedTarget,
     playlist_id: &str,
     playlist_directory: &PathBuf,
-    yt_dlp_arguments: &str,
+    options: &DownloadOptions<'_>,
     verbose: bool,
 ) -> Result<(), Box<dyn Error>> {
-    let playlist_data = fetch_playlist_data(playlist_id)?;
+    let playlist_data = match target {
+        ResolvedTarget::Video(video_id) => fetch_video_data(video_id)?,
+        _ => fetch_playlist_data(playlist_id)?,
+    };
     if verbose {
-        println!("Fetched Playlist Data: {playlist_data:?}");
+        println!(
+            "Fetched playlist \"{}\" with {} songs:",
+            playlist_data.title,
+            playlist_data.entries.len()
+        );
+        for song in &playlist_data.entries {
+            println!("  {}", song.describe());
+        }
     }
 
-    download_songs(&playlist_data.entries, playlist_directory, yt_dlp_arguments)?;
+    download_songs(&playlist_data.entries, playlist_directory, options).await?;
+
+    // Persist the playlist so later non-refresh and offline runs know the
+    // intended title and order without re-querying.
+    let playlist_json = serde_json::to_string_pretty(&playlist_data)?;
+    fs::write(playlist_directory.join("playlist.json"), playlist_json)?;
 
     Ok(())
 }
 
 fn play_songs(
     playlist_directory: &PathBuf,
+    playlist: Option<&Playlist>,
     shuffle: bool,
     mpv_arguments: &str,
 ) -> Result<(), Box<dyn Error>> {
@@ -223,19 +706,75 @@ fn play_songs(
         mpv.args(mpv_arguments.split_whitespace());
     }
 
-    mpv.arg(".").status()?;
+    // With a known order and no shuffle, hand mpv an ordered playlist file so
+    // songs play in the playlist's sequence rather than alphabetically.
+    match playlist {
+        Some(playlist) if !shuffle => {
+            build_m3u(playlist, playlist_directory)?;
+            mpv.arg("--playlist=playlist.m3u8");
+        }
+        _ => {
+            mpv.arg(".");
+        }
+    }
+
+    mpv.status()?;
 
     Ok(())
 }
 
-fn run() -> Result<(), Box<dyn Error>> {
+async fn run() -> Result<(), Box<dyn Error>> {
     let cli = Cli::parse();
 
-    let url = cli.url;
+    if cli.list_cached {
+        return list_cached();
+    }
 
-    let id = extract_id(&url)?;
+    let config = load_config()?;
+
+    // With no URL, replay the last playlist the user asked for.
+    let url = match cli.url {
+        Some(url) => url,
+        None => read_last_url()?.ok_or_else(|| {
+            PlaylistError("No URL provided and no previous playlist to replay".to_string())
+        })?,
+    };
+
+    // CLI flags override config, which overrides the built-in defaults.
+    let yt_dlp_arguments = cli
+        .yt_dlp_arguments
+        .or(config.yt_dlp_arguments)
+        .unwrap_or_default();
+    let mpv_arguments = cli.mpv_arguments.or(config.mpv_arguments).unwrap_or_default();
+    let shuffle = cli.shuffle || config.shuffle;
+    let parallel = cli.parallel.or(config.parallel).unwrap_or(4);
+    let format = cli
+        .format
+        .or_else(|| {
+            config
+                .format
+                .as_deref()
+                .and_then(|f| Format::from_str(f, true).ok())
+        })
+        .unwrap_or_default();
+    let download_options = DownloadOptions {
+        yt_dlp_arguments: &yt_dlp_arguments,
+        parallel,
+        format,
+        embed_metadata: cli.embed_metadata,
+    };
+
+    let target = extract_id(&url)?;
+
+    // Channels resolve to their uploads playlist; everything else caches under
+    // its own id.
+    let id = match &target {
+        ResolvedTarget::Playlist(id) => id.clone(),
+        ResolvedTarget::Video(id) => id.clone(),
+        ResolvedTarget::Channel(channel_url) => resolve_channel_uploads(channel_url)?,
+    };
     if cli.verbose {
-        println!("Found Playlist ID: {id}");
+        println!("Found ID: {id}");
     }
 
     let playlist_directory = get_playlist_directory(&id)?;
@@ -243,41 +782,183 @@ fn run() -> Result<(), Box<dyn Error>> {
         println!("Using Cache Directory: {}", playlist_directory.display());
     }
 
-    if !fs::exists(&playlist_directory)? {
-        fs::create_dir_all(&playlist_directory).map_err(|e| {
-            format!(
-                "Failed to create cache directory at {}: {}",
-                playlist_directory.display(),
-                e
+    let exists = fs::exists(&playlist_directory)?;
+
+    if cli.offline {
+        if !exists {
+            return Err(Box::new(PlaylistError(
+                "Nothing is cached for this URL to play offline".to_string(),
+            )));
+        }
+    } else {
+        if !exists {
+            fs::create_dir_all(&playlist_directory).map_err(|e| {
+                format!(
+                    "Failed to create cache directory at {}: {}",
+                    playlist_directory.display(),
+                    e
+                )
+            })?;
+        }
+
+        if !exists || cli.refresh {
+            // A metadata fetch failure (no network, unavailable playlist) falls
+            // back to whatever is already cached rather than aborting.
+            if let Err(e) = update_playlist(
+                &target,
+                &id,
+                &playlist_directory,
+                &download_options,
+                cli.verbose,
             )
-        })?;
-        update_playlist(
-            &id,
-            &playlist_directory,
-            &cli.yt_dlp_arguments.unwrap_or(String::new()),
-            cli.verbose,
-        )?;
-    } else if cli.refresh {
-        update_playlist(
-            &id,
-            &playlist_directory,
-            &cli.yt_dlp_arguments.unwrap_or(String::new()),
-            cli.verbose,
-        )?;
-    }
-
-    play_songs(
-        &playlist_directory,
-        cli.shuffle,
-        &cli.mpv_arguments.unwrap_or(String::new()),
-    )?;
+            .await
+            {
+                eprintln!("Warning: could not update playlist, falling back to cache: {e}");
+            }
+        }
+    }
+
+    let playlist = load_cached_playlist(&playlist_directory);
+    play_songs(&playlist_directory, playlist.as_ref(), shuffle, &mpv_arguments)?;
+
+    // Only remember the URL once it's actually played, so a bad URL, an
+    // unreachable channel, or an uncached --offline id doesn't clobber the
+    // last-known-good playlist.
+    write_last_url(&url)?;
 
     Ok(())
 }
 
-fn main() {
-    if let Err(e) = run() {
+#[tokio::main]
+async fn main() {
+    if let Err(e) = run().await {
         eprintln!("Error: {e}");
         std::process::exit(1);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extract_id_resolves_watch_url_to_video() {
+        let target = extract_id("https://www.youtube.com/watch?v=abc123").unwrap();
+        assert!(matches!(target, ResolvedTarget::Video(id) if id == "abc123"));
+    }
+
+    #[test]
+    fn extract_id_resolves_watch_url_with_list_to_playlist() {
+        let target =
+            extract_id("https://www.youtube.com/watch?v=abc123&list=PLxyz").unwrap();
+        assert!(matches!(target, ResolvedTarget::Playlist(id) if id == "PLxyz"));
+    }
+
+    #[test]
+    fn extract_id_resolves_bare_youtu_be_to_video() {
+        let target = extract_id("https://youtu.be/abc123").unwrap();
+        assert!(matches!(target, ResolvedTarget::Video(id) if id == "abc123"));
+    }
+
+    #[test]
+    fn extract_id_resolves_youtu_be_with_list_to_playlist() {
+        // youtu.be/<id>?list=...&index=N is what YouTube produces when
+        // sharing a video from inside a playlist; the list must win.
+        let target =
+            extract_id("https://youtu.be/abc123?list=PLxyz&index=3").unwrap();
+        assert!(matches!(target, ResolvedTarget::Playlist(id) if id == "PLxyz"));
+    }
+
+    #[test]
+    fn extract_id_resolves_channel_urls() {
+        for url in [
+            "https://www.youtube.com/channel/UCxyz",
+            "https://www.youtube.com/@somehandle",
+            "https://www.youtube.com/user/someuser",
+        ] {
+            assert!(matches!(
+                extract_id(url).unwrap(),
+                ResolvedTarget::Channel(_)
+            ));
+        }
+    }
+
+    #[test]
+    fn extract_id_rejects_unrecognized_url() {
+        assert!(extract_id("https://example.com/").is_err());
+    }
+
+    #[test]
+    fn extract_id_rejects_non_youtube_hosts_with_lookalike_paths() {
+        assert!(extract_id("https://example.com/watch?v=abc123").is_err());
+        assert!(extract_id("https://example.com/@someone").is_err());
+        assert!(extract_id("https://example.com/channel/UCxyz").is_err());
+        assert!(extract_id("https://example.com/user/someuser").is_err());
+    }
+
+    fn song(title: &str) -> Song {
+        Song {
+            id: "abc123".to_string(),
+            title: title.to_string(),
+            duration: None,
+            uploader: None,
+            channel: None,
+            availability: None,
+            live_status: None,
+        }
+    }
+
+    #[test]
+    fn is_playable_accepts_ordinary_song() {
+        assert!(song("Some Song").is_playable());
+    }
+
+    #[test]
+    fn is_playable_rejects_unavailable_entries() {
+        let mut s = song("Some Song");
+        s.availability = Some("private".to_string());
+        assert!(!s.is_playable());
+    }
+
+    #[test]
+    fn is_playable_rejects_deleted_placeholder_titles() {
+        assert!(!song("[Deleted video]").is_playable());
+    }
+
+    #[test]
+    fn is_playable_rejects_live_and_upcoming_entries() {
+        let mut s = song("Some Song");
+        s.live_status = Some("is_live".to_string());
+        assert!(!s.is_playable());
+
+        let mut s = song("Some Song");
+        s.live_status = Some("is_upcoming".to_string());
+        assert!(!s.is_playable());
+    }
+
+    #[test]
+    fn parse_progress_reads_percentage_from_download_line() {
+        assert_eq!(
+            parse_progress("[download]  42.0% of 3.14MiB at 1.00MiB/s ETA 00:01"),
+            Some(42.0)
+        );
+    }
+
+    #[test]
+    fn parse_progress_ignores_unrelated_lines() {
+        assert_eq!(parse_progress("[ExtractAudio] Destination: song.opus"), None);
+    }
+
+    #[test]
+    fn format_duration_pads_seconds() {
+        assert_eq!(format_duration(65.0), "1:05");
+        assert_eq!(format_duration(3.0), "0:03");
+    }
+
+    #[test]
+    fn human_size_picks_largest_whole_unit() {
+        assert_eq!(human_size(512), "512.0 B");
+        assert_eq!(human_size(1536), "1.5 KiB");
+        assert_eq!(human_size(5 * 1024 * 1024), "5.0 MiB");
+    }
+}